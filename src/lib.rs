@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use std::cell::{Cell as DirtyFlag, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 /// `InputCellID` is a unique identifier for an input cell.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -20,7 +22,7 @@ pub struct InputCellID(u64);
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ComputeCellID(u64);
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct CallbackID();
+pub struct CallbackID(u64);
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CellID {
@@ -45,8 +47,15 @@ pub enum RemoveCallbackError {
 
 
 struct ComputeCell<T> {
-    func: Box<Fn(&[T]) -> T>,
+    // Shared by reference so the surrounding `Reactor` can be cloned cheaply:
+    // a fork keeps pointing at the same compute logic rather than owning it.
+    func: Rc<Fn(&[T]) -> T>,
     _deps: Vec<CellID>,
+    callbacks: HashMap<u64, Box<FnMut(T)>>,
+    // Demand-driven cache: `value` holds the last computed result and `dirty`
+    // records whether an upstream input has moved since it was taken.
+    value: RefCell<T>,
+    dirty: DirtyFlag<bool>,
 }
 
 struct InputCell<T> {
@@ -58,33 +67,104 @@ enum Cell<T> {
     Input(InputCell<T>),
 }
 
+// Cloning shares each compute cell's function via its `Rc` and copies the
+// cached value and dirty flag. Callbacks are not carried over: a `Box<FnMut>`
+// cannot be cloned, and a fork conceptually starts its own observers, so a
+// cloned reactor begins callback-free.
+impl<T: Clone> Clone for ComputeCell<T> {
+    fn clone(&self) -> Self {
+        ComputeCell {
+            func: Rc::clone(&self.func),
+            _deps: self._deps.clone(),
+            callbacks: HashMap::new(),
+            value: RefCell::new(self.value.borrow().clone()),
+            dirty: DirtyFlag::new(self.dirty.get()),
+        }
+    }
+}
+
+impl<T: Clone> Clone for Cell<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Cell::Input(inputcell) => Cell::Input(InputCell { val: inputcell.val.clone() }),
+            Cell::Compute(computecell) => Cell::Compute(computecell.clone()),
+        }
+    }
+}
+
+impl<T: Clone> Clone for Reactor<T> {
+    fn clone(&self) -> Self {
+        Reactor {
+            id: self.id,
+            callback_id: self.callback_id,
+            cells: self.cells.iter().map(|(id, cell)| (*id, cell.clone())).collect(),
+            dependents: self.dependents.clone(),
+        }
+    }
+}
+
 pub struct Reactor<T> {
     id: u64,
+    callback_id: u64,
     cells: HashMap<u64, Cell<T>>,
+    // Reverse edges: for each cell id, the compute cells that read from it.
+    // Built incrementally in `create_compute` and used to propagate dirtiness.
+    dependents: HashMap<u64, Vec<u64>>,
 }
 
-impl<T: Copy + PartialEq> Cell<T> {
-    fn get_val(&self, reactor: &Reactor<T>) -> T {
-        match self {
+// `Reactor` works with any `Clone + PartialEq` type, including heap-backed ones
+// like `String` and `Vec`: values are cloned when building the argument slice
+// handed to a compute function and when returned from `value`.
+impl <T: Clone + PartialEq> Reactor<T> {
+    pub fn new() -> Self {
+        Reactor { id: 0, callback_id: 0, cells: HashMap::new(), dependents: HashMap::new() }
+    }
+
+    // Returns the up-to-date value of a cell, recomputing only the dirty
+    // portion of the graph. A clean compute cell returns its cache directly;
+    // a dirty one first ensures each of its dependencies is current, reruns its
+    // function, stores the result and marks itself clean. Repeated queries
+    // against a settled graph are therefore O(1).
+    //
+    // Note there is deliberately no value-equality short-circuit here: a dirty
+    // cell is always recomputed. Whether a change is observable (and thus fires
+    // callbacks) is decided by comparing the before/after snapshots taken in
+    // `set_values`, not by propagation through this method.
+    fn get_val(&self, id: u64) -> T {
+        match self.cells.get(&id).unwrap() {
+            Cell::Input(inputcell) => inputcell.val.clone(),
             Cell::Compute(computecell) => {
-                let mut computed = Vec::new();
-                for dep in computecell._deps.iter() {
-                    let id = dep.get_id();
-                    let cell = reactor.cells.get(&id).unwrap();
-                    computed.push(cell.get_val(&reactor));
+                if computecell.dirty.get() {
+                    let mut computed = Vec::new();
+                    for dep in computecell._deps.iter() {
+                        computed.push(self.get_val(dep.get_id()));
+                    }
+                    let val = (computecell.func)(&computed);
+                    *computecell.value.borrow_mut() = val;
+                    computecell.dirty.set(false);
                 }
-                let func = &computecell.func;
-                func(&computed)
+                computecell.value.borrow().clone()
             },
-            Cell::Input(inputcell) => inputcell.val,
         }
     }
-}
 
-// You are guaranteed that Reactor will only be tested against types that are Copy + PartialEq.
-impl <T: Copy + PartialEq> Reactor<T> {
-    pub fn new() -> Self {
-        Reactor { id: 0, cells: HashMap::new() }
+    // Marks every compute cell transitively downstream of `id` as dirty by
+    // walking the reverse-edge map. The `visited` set ensures each cell is
+    // marked at most once: without it a diamond/lattice would re-walk shared
+    // cells once per distinct path, making propagation exponential rather than
+    // linear in the number of affected edges.
+    fn mark_dirty(&self, id: u64, visited: &mut HashSet<u64>) {
+        if let Some(deps) = self.dependents.get(&id) {
+            for dep in deps {
+                if !visited.insert(*dep) {
+                    continue;
+                }
+                if let Some(Cell::Compute(computecell)) = self.cells.get(dep) {
+                    computecell.dirty.set(true);
+                }
+                self.mark_dirty(*dep, visited);
+            }
+        }
     }
 
     // Creates an input cell with the specified initial value, returning its ID.
@@ -118,18 +198,24 @@ impl <T: Copy + PartialEq> Reactor<T> {
             };
             match self.cells.get(&id) {
                 None => return Err(*dep),
-                Some(cell) => {
-                    let val = cell.get_val(&self);
-                    cellcontents.push(val);
+                Some(_) => {
+                    cellcontents.push(self.get_val(id));
                 },
             };
         }
 
         let id = self.id;
         self.id = self.id + 1;
+        for dep in dependencies {
+            self.dependents.entry(dep.get_id()).or_insert_with(Vec::new).push(id);
+        }
+        let initial = compute_func(&cellcontents);
         let cell: ComputeCell<T> = ComputeCell {
-            func: Box::new(compute_func),
+            func: Rc::new(compute_func),
             _deps: dependencies.to_owned(),
+            callbacks: HashMap::new(),
+            value: RefCell::new(initial),
+            dirty: DirtyFlag::new(false),
         };
 
         self.cells.insert(id, Cell::Compute(cell));
@@ -145,24 +231,11 @@ impl <T: Copy + PartialEq> Reactor<T> {
     // It turns out this introduces a significant amount of extra complexity to this exercise.
     // We chose not to cover this here, since this exercise is probably enough work as-is.
     pub fn value(&self, id: CellID) -> Option<T> {
-        match id {
-            CellID::Input(cell_id) => {
-                let cell = match self.cells.get(&cell_id.0) {
-                    Some(cell) => cell,
-                    None => return None,
-                };
-                
-                Some(cell.get_val(&self))
-            },
-            CellID::Compute(cell_id) => {
-                let cell = match self.cells.get(&cell_id.0) {
-                    Some(k) => k,
-                    None => return None,
-                };
-
-                Some(cell.get_val(&self))
-            },
+        let cell_id = id.get_id();
+        if !self.cells.contains_key(&cell_id) {
+            return None;
         }
+        Some(self.get_val(cell_id))
     }
 
     // Sets the value of the specified input cell.
@@ -174,17 +247,75 @@ impl <T: Copy + PartialEq> Reactor<T> {
     //
     // As before, that turned out to add too much extra complexity.
     pub fn set_value(&mut self, _id: InputCellID, new_value: T) -> bool {
-        match self.cells.get(&_id.0) {
-            None => return false,
-            Some(cell) => {
-                if let Cell::Compute(_) = cell { return false; };
-            },
-        };
+        self.set_values(&[(_id, new_value)])
+    }
+
+    // Applies several input writes as a single transaction, then stabilizes the
+    // graph once and fires callbacks.
+    //
+    // Returns false (writing nothing) if any of the targeted cells does not
+    // exist or is not an input cell.
+    //
+    // Because the whole batch is applied before anything is recomputed, a
+    // compute cell fed by two of the updated inputs never observes an
+    // intermediate state, and its callbacks run at most once with the settled
+    // value regardless of how many inputs moved beneath it.
+    pub fn set_values(&mut self, updates: &[(InputCellID, T)]) -> bool {
+        for (id, _) in updates {
+            match self.cells.get(&id.0) {
+                Some(Cell::Input(_)) => {},
+                _ => return false,
+            };
+        }
 
-        let new_cell = InputCell { val: new_value };
+        // Only the compute cells reachable from the updated inputs can change,
+        // so restrict the snapshot and comparison to that cone rather than
+        // walking the whole graph on every write.
+        let mut affected = HashSet::new();
+        for (id, _) in updates {
+            self.collect_affected(id.0, &mut affected);
+        }
+        let before: HashMap<u64, T> = affected.iter()
+            .map(|id| (*id, self.get_val(*id)))
+            .collect();
+
+        // Apply all writes first, dirtying their downstream cones, so that the
+        // recompute below sees the fully settled set of inputs.
+        let mut visited = HashSet::new();
+        for (id, new_value) in updates {
+            self.cells.insert(id.0, Cell::Input(InputCell { val: new_value.clone() }));
+            self.mark_dirty(id.0, &mut visited);
+        }
+
+        // Recompute once and fire each changed cell's callbacks exactly once
+        // with its final value. A diamond dependency is handled naturally: the
+        // comparison is per cell, not per path.
+        let after: HashMap<u64, T> = affected.iter()
+            .map(|id| (*id, self.get_val(*id)))
+            .collect();
+        for id in affected {
+            if before[&id] == after[&id] { continue; }
+            let val = after[&id].clone();
+            if let Some(Cell::Compute(cell)) = self.cells.get_mut(&id) {
+                for callback in cell.callbacks.values_mut() {
+                    callback(val.clone());
+                }
+            }
+        }
+        true
+    }
 
-        self.cells.insert(_id.0, Cell::Input(new_cell)).unwrap();
-        return true;
+    // Collects into `affected` every compute cell transitively downstream of
+    // `id`, guarded against the re-visits a diamond/lattice would cause so the
+    // walk stays linear in the number of affected edges.
+    fn collect_affected(&self, id: u64, affected: &mut HashSet<u64>) {
+        if let Some(deps) = self.dependents.get(&id) {
+            for dep in deps {
+                if affected.insert(*dep) {
+                    self.collect_affected(*dep, affected);
+                }
+            }
+        }
     }
 
     // Adds a callback to the specified compute cell.
@@ -199,8 +330,16 @@ impl <T: Copy + PartialEq> Reactor<T> {
     // * Exactly once if the compute cell's value changed as a result of the set_value call.
     //   The value passed to the callback should be the final value of the compute cell after the
     //   set_value call.
-    pub fn add_callback<F: FnMut(T) -> ()>(&mut self, _id: ComputeCellID, _callback: F) -> Option<CallbackID> {
-        unimplemented!()
+    pub fn add_callback<F: FnMut(T) -> () + 'static>(&mut self, _id: ComputeCellID, _callback: F) -> Option<CallbackID> {
+        let callback_id = self.callback_id;
+        match self.cells.get_mut(&_id.0) {
+            Some(Cell::Compute(cell)) => {
+                cell.callbacks.insert(callback_id, Box::new(_callback));
+            },
+            _ => return None,
+        };
+        self.callback_id = self.callback_id + 1;
+        Some(CallbackID(callback_id))
     }
 
     // Removes the specified callback, using an ID returned from add_callback.
@@ -209,10 +348,93 @@ impl <T: Copy + PartialEq> Reactor<T> {
     //
     // A removed callback should no longer be called.
     pub fn remove_callback(&mut self, cell: ComputeCellID, callback: CallbackID) -> Result<(), RemoveCallbackError> {
-        unimplemented!(
-            "Remove the callback identified by the CallbackID {:?} from the cell {:?}",
-            callback,
-            cell,
-        )
+        match self.cells.get_mut(&cell.0) {
+            Some(Cell::Compute(computecell)) => {
+                match computecell.callbacks.remove(&callback.0) {
+                    Some(_) => Ok(()),
+                    None => Err(RemoveCallbackError::NonexistentCallback),
+                }
+            },
+            _ => Err(RemoveCallbackError::NonexistentCell),
+        }
+    }
+
+    // Returns the direct dependencies of a compute cell, in the order they were
+    // declared at `create_compute` time, or None if no such compute cell exists.
+    pub fn dependencies(&self, id: ComputeCellID) -> Option<Vec<CellID>> {
+        match self.cells.get(&id.0) {
+            Some(Cell::Compute(computecell)) => Some(computecell._deps.clone()),
+            _ => None,
+        }
+    }
+
+    // Returns the compute cells that read directly from the given cell. An
+    // unknown cell, or one that nothing depends on, yields an empty vector.
+    pub fn dependents(&self, id: CellID) -> Vec<CellID> {
+        match self.dependents.get(&id.get_id()) {
+            Some(deps) => deps.iter().map(|dep| CellID::Compute(ComputeCellID(*dep))).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Returns every cell in dependency order: a cell always appears after all of
+    // the cells it depends on. Cells are otherwise taken in creation order, so
+    // an input created after a compute cell appears after that compute cell.
+    pub fn topological_order(&self) -> Vec<CellID> {
+        let mut ids: Vec<u64> = self.cells.keys().cloned().collect();
+        ids.sort();
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for id in ids {
+            self.topo_visit(id, &mut visited, &mut order);
+        }
+        order
+    }
+
+    fn topo_visit(&self, id: u64, visited: &mut HashSet<u64>, order: &mut Vec<CellID>) {
+        if !visited.insert(id) {
+            return;
+        }
+        if let Some(Cell::Compute(computecell)) = self.cells.get(&id) {
+            for dep in computecell._deps.iter() {
+                self.topo_visit(dep.get_id(), visited, order);
+            }
+        }
+        order.push(self.cell_id(id));
+    }
+
+    // Wraps a raw id back into the `CellID` variant matching its kind.
+    fn cell_id(&self, id: u64) -> CellID {
+        match self.cells.get(&id) {
+            Some(Cell::Input(_)) => CellID::Input(InputCellID(id)),
+            _ => CellID::Compute(ComputeCellID(id)),
+        }
+    }
+
+    // Serializes the whole graph to Graphviz DOT. Input cells are drawn as
+    // boxes and compute cells as ellipses; each edge runs from a dependency to
+    // the compute cell that consumes it.
+    pub fn dump_dot(&self) -> String {
+        let mut ids: Vec<u64> = self.cells.keys().cloned().collect();
+        ids.sort();
+
+        let mut out = String::from("digraph reactor {\n");
+        for id in ids.iter() {
+            match self.cells.get(id).unwrap() {
+                Cell::Input(_) =>
+                    out.push_str(&format!("    {} [shape=box, label=\"input {}\"];\n", id, id)),
+                Cell::Compute(_) =>
+                    out.push_str(&format!("    {} [shape=ellipse, label=\"compute {}\"];\n", id, id)),
+            }
+        }
+        for id in ids.iter() {
+            if let Some(Cell::Compute(computecell)) = self.cells.get(id) {
+                for dep in computecell._deps.iter() {
+                    out.push_str(&format!("    {} -> {};\n", dep.get_id(), id));
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
     }
 }